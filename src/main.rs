@@ -1,4 +1,5 @@
 use bevy::{
+    audio::{Audio, AudioSource},
     input::{keyboard::KeyCode, Input},
     prelude::*,
     sprite::MaterialMesh2dBundle,
@@ -10,22 +11,45 @@ use rand::Rng;
 fn main() {
     App::new()
         .insert_resource(ClearColor(Color::BLACK)) // Sets background color to black
+        .insert_resource(GameConfig::from_args())
+        .insert_resource(ServeCountdown(SERVE_COUNTDOWN_SECONDS))
         .add_plugins(DefaultPlugins)
+        .add_state(GameState::Serving)
         .add_startup_system(setup)
         .add_system_set(
             SystemSet::new()
             .with_run_criteria(FixedTimestep::step(1. / 60.))
             .with_system(move_paddle)
+            .with_system(ai_paddle)
             .with_system(move_ball)
             .with_system(collision.after(move_ball).after(move_paddle))
             .with_system(score.after(move_ball))
+            .with_system(check_for_win.after(score))
         )
+        .add_system_set(SystemSet::on_enter(GameState::Serving).with_system(enter_serving))
+        .add_system_set(SystemSet::on_update(GameState::Serving).with_system(serve_countdown))
+        .add_system_set(SystemSet::on_update(GameState::GameOver).with_system(restart_game))
         .add_event::<BallResetEvent>()
+        .add_event::<PaddleHitEvent>()
+        .add_event::<WallHitEvent>()
+        .add_event::<WinEvent>()
         .add_system(reset_ball)
-        .add_system(check_for_win)
+        .add_system(toggle_pause)
+        .add_system(play_paddle_hit_sound)
+        .add_system(play_wall_hit_sound)
+        .add_system(play_score_sound)
+        .add_system(play_win_sound)
         .run();
 }
 
+#[derive(Clone, Eq, PartialEq, Debug, Hash)]
+enum GameState {
+    Serving,
+    Playing,
+    Paused,
+    GameOver,
+}
+
 #[derive(Component)]
 struct Score (u16);
 
@@ -49,6 +73,20 @@ struct BallStartingPoint {
 struct DistanceFromStartingPoint (f32);
 
 #[derive(Component)]
+struct BallSpeed (f32);
+
+// Marks the text entity that shows the serve countdown
+#[derive(Component)]
+struct CountdownText;
+
+// Marks the text entity that shows the winner and restart prompt
+#[derive(Component)]
+struct GameOverText;
+
+// Seconds remaining before the ball launches after entering GameState::Serving
+struct ServeCountdown(f32);
+
+#[derive(Component, Clone, Copy)]
 enum Collision {
     Left,
     Right,
@@ -56,26 +94,88 @@ enum Collision {
     Bottom,
 }
 
+// Marks the paddle steered by `ai_paddle` instead of the keyboard
+#[derive(Component)]
+struct AiControlled;
+
+enum GameMode {
+    OnePlayer,
+    TwoPlayer,
+}
+
+struct GameConfig {
+    mode: GameMode,
+    // 0. (never moves) to 1. (moves at full PADDLE_SPEED)
+    ai_difficulty: f32,
+}
+
+impl GameConfig {
+    // Reads `--two-player` from argv; single-player against the AI is the default.
+    fn from_args() -> Self {
+        let mode = if std::env::args().any(|arg| arg == "--two-player") {
+            GameMode::TwoPlayer
+        } else {
+            GameMode::OnePlayer
+        };
+
+        GameConfig {
+            mode,
+            ai_difficulty: 0.6,
+        }
+    }
+}
+
 const TABLE_SIZE: [f32; 2] = [1400., 700.]; // [x, y]
 
 const PADDLE_LENGTH: f32 = 100.;
 const PADDLE_WIDTH: f32 = 20.;
 const PADDLE_SPEED: f32 = 5.0;
+const AI_DEADZONE: f32 = 10.;
+
+const WINNING_SCORE: u16 = 10;
+const SERVE_COUNTDOWN_SECONDS: f32 = 1.5;
 
 const BALL_RADIUS: f32 = 15.;
 const BALL_SPEED: f32 = 6.;
+const BALL_SPEEDUP_MULTIPLIER: f32 = 1.05;
+const BALL_MAX_SPEED: f32 = 18.;
+
+// Maximum angle, in radians away from the horizontal, a paddle hit can send the ball
+const MAX_BOUNCE_ANGLE: f32 = 1.3;
 
 struct BallResetEvent;
 
+// Fired by `collision` (paddle and wall hits) and `check_for_win` (wins) so audio stays
+// decoupled from the game logic it reacts to
+struct PaddleHitEvent;
+struct WallHitEvent;
+struct WinEvent;
+
+struct SoundAssets {
+    paddle_hit: Handle<AudioSource>,
+    wall_hit: Handle<AudioSource>,
+    score: Handle<AudioSource>,
+    win: Handle<AudioSource>,
+}
+
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     asset_server: Res<AssetServer>,
+    config: Res<GameConfig>,
 ) {
 
     commands.spawn_bundle(Camera2dBundle::default());
 
+    // Load sound effects up front so they're ready the first time they're needed
+    commands.insert_resource(SoundAssets {
+        paddle_hit: asset_server.load("sounds/paddle_hit.ogg"),
+        wall_hit: asset_server.load("sounds/wall_hit.ogg"),
+        score: asset_server.load("sounds/score.ogg"),
+        win: asset_server.load("sounds/win.ogg"),
+    });
+
     // Generating big white cube for border
     commands.spawn_bundle(SpriteBundle {
         sprite: Sprite {
@@ -160,7 +260,7 @@ fn setup(
     .insert(Side::Left);
 
     // Right player
-    commands.spawn_bundle(SpriteBundle {
+    let mut right_paddle = commands.spawn_bundle(SpriteBundle {
         sprite: Sprite {
             color: Color::WHITE,
             custom_size: Some(Vec2::new(PADDLE_WIDTH, PADDLE_LENGTH)),
@@ -168,9 +268,11 @@ fn setup(
         },
         transform: Transform::from_xyz(600., 0., 2.),
         ..default()
-    })
-    .insert(Score (0))
-    .insert(Side::Right);
+    });
+    right_paddle.insert(Score (0)).insert(Side::Right);
+    if let GameMode::OnePlayer = config.mode {
+        right_paddle.insert(AiControlled);
+    }
 
     // The ball
     commands.spawn_bundle(MaterialMesh2dBundle {
@@ -181,14 +283,46 @@ fn setup(
     })
     .insert(BallDirection (ball_first_direction()))
     .insert(BallStartingPoint { x: 0., y: 0. })
-    .insert(DistanceFromStartingPoint (0.));
+    .insert(DistanceFromStartingPoint (0.))
+    .insert(BallSpeed (BALL_SPEED));
+
+    // Serve countdown, shown just above the ball while GameState::Serving
+    commands.spawn_bundle(Text2dBundle {
+        text: Text::from_section("", TextStyle {
+            font: asset_server.load("Lato-Bold.ttf"),
+            font_size: 80.,
+            color: Color::WHITE,
+        })
+            .with_alignment(TextAlignment::CENTER),
+        transform: Transform::from_xyz(0., 100., 4.),
+        ..default()
+    })
+    .insert(CountdownText);
+
+    // Win banner and restart prompt, shown while GameState::GameOver
+    commands.spawn_bundle(Text2dBundle {
+        text: Text::from_section("", TextStyle {
+            font: asset_server.load("Lato-Bold.ttf"),
+            font_size: 50.,
+            color: Color::WHITE,
+        })
+            .with_alignment(TextAlignment::CENTER),
+        transform: Transform::from_xyz(0., 0., 4.),
+        ..default()
+    })
+    .insert(GameOverText);
 }
 
 fn move_paddle(
     keyboard_input: Res<Input<KeyCode>>,
-    mut query: Query<(&mut Transform, &Side), With<Score>>,
+    mut query: Query<(&mut Transform, &Side), (With<Score>, Without<AiControlled>)>,
+    state: Res<State<GameState>>,
 ) {
 
+    if *state.current() != GameState::Playing {
+        return;
+    }
+
     for (mut transform, side) in &mut query {
 
         // Moves paddle
@@ -222,23 +356,75 @@ fn move_paddle(
     }
 }
 
+fn ai_paddle(
+    config: Res<GameConfig>,
+    ball_query: Query<(&Transform, &BallDirection), Without<Score>>,
+    mut paddle_query: Query<&mut Transform, (With<Score>, With<AiControlled>)>,
+    state: Res<State<GameState>>,
+) {
+
+    if *state.current() != GameState::Playing {
+        return;
+    }
+
+    // Only the right paddle is ever AI-controlled, and only in one-player mode
+    if !matches!(config.mode, GameMode::OnePlayer) {
+        return;
+    }
+
+    for (ball_transform, ball_direction) in &ball_query {
+
+        // Only react once the ball is actually headed towards the AI paddle
+        let ball_moving_towards_ai = ball_direction.0.cos() > 0.;
+        if !ball_moving_towards_ai {
+            continue;
+        }
+
+        // Difficulty caps the AI's reaction speed below the player's, so it's beatable
+        let ai_speed = PADDLE_SPEED * config.ai_difficulty;
+
+        for mut transform in &mut paddle_query {
+            if ball_transform.translation.y > transform.translation.y + AI_DEADZONE {
+                transform.translation.y += ai_speed;
+            } else if ball_transform.translation.y < transform.translation.y - AI_DEADZONE {
+                transform.translation.y -= ai_speed;
+            }
+
+            // Doesn't let paddle exit game zone
+            if transform.translation.y + PADDLE_LENGTH / 2. >= TABLE_SIZE[1] / 2. {
+                transform.translation.y -= ai_speed;
+            } else if transform.translation.y - PADDLE_LENGTH / 2. <= -TABLE_SIZE[1] / 2. {
+                transform.translation.y += ai_speed;
+            }
+        }
+    }
+}
+
 fn move_ball(
     mut query: Query<(
         &mut Transform,
         &BallDirection,
         &BallStartingPoint,
         &mut DistanceFromStartingPoint,
-    )>) {
+        &BallSpeed,
+    )>,
+    state: Res<State<GameState>>,
+) {
+
+    if *state.current() != GameState::Playing {
+        return;
+    }
 
     for (
         mut transform,
         direction,
         starting_point,
-        mut distance
+        mut distance,
+        speed,
         ) in &mut query {
 
         // Distance between ball and ball starting point
-        distance.0 += BALL_SPEED;
+        distance.0 += speed.0;
 
         // Sarting point x coordinate
         let x1 = starting_point.x;
@@ -256,79 +442,98 @@ fn move_ball(
     }
 }
 
+// Axis-aligned bounding box used by `collision` to test the ball (treated as a bounding
+// circle) against the paddles, replacing the old fixed-tolerance windows with exact,
+// speed-independent intersection math. The walls are not boxes: `collision` checks them
+// by comparing the ball's y with the table edges.
+struct Aabb2d {
+    min: Vec2,
+    max: Vec2,
+}
+
+impl Aabb2d {
+    fn new(center: Vec2, half_size: Vec2) -> Self {
+        Aabb2d {
+            min: center - half_size,
+            max: center + half_size,
+        }
+    }
+
+    fn closest_point(&self, point: Vec2) -> Vec2 {
+        point.clamp(self.min, self.max)
+    }
+}
+
 fn collision(
     mut ball_query: Query<(
         &Transform,
         &mut BallDirection,
         &mut BallStartingPoint,
-        &mut DistanceFromStartingPoint
+        &mut DistanceFromStartingPoint,
+        &mut BallSpeed,
     )>,
     paddle_query: Query<(&Transform, &Side), With<Score>>,
+    mut paddle_hit_events: EventWriter<PaddleHitEvent>,
+    mut wall_hit_events: EventWriter<WallHitEvent>,
+    state: Res<State<GameState>>,
 ) {
 
+    if *state.current() != GameState::Playing {
+        return;
+    }
+
     for (transform,
         mut direction,
         mut starting_point,
         mut distance,
+        mut speed,
         ) in &mut ball_query {
 
+        let ball_center = transform.translation.truncate();
+
         // Check for collision with play area
-        let mut collision = match transform.translation.y {
+        let mut collision = match ball_center.y {
             y if y + BALL_RADIUS >= TABLE_SIZE[1] / 2. => Some(Collision::Top),
             y if y - BALL_RADIUS <= -TABLE_SIZE[1] / 2. => Some(Collision::Bottom),
             _ => None
         };
 
+        // y coordinate of the paddle that was hit, used to work out where on the
+        // paddle the ball struck for Collision::Left / Collision::Right
+        let mut hit_paddle_y = 0.;
+
         // If no play area collision found check for collision with paddle
         if collision.is_none() {
             for (paddle_transform, paddle_side) in &paddle_query {
-
-                // Check if ball is past paddles on x axis
-                match paddle_side {
-                    Side::Right => {
-                        if transform.translation.x + BALL_RADIUS >= paddle_transform.translation.x + PADDLE_WIDTH / 2. {break}
-                    },
-                    Side::Left => {
-                        if transform.translation.x - BALL_RADIUS <= paddle_transform.translation.x - PADDLE_WIDTH / 2. {break}
-                    }
+                let paddle_center = paddle_transform.translation.truncate();
+                let paddle_box = Aabb2d::new(paddle_center, Vec2::new(PADDLE_WIDTH / 2., PADDLE_LENGTH / 2.));
+
+                // Ball-as-circle vs paddle-as-box: no hit unless the closest point on the
+                // box is within the ball's radius, regardless of how fast the ball is moving
+                let offset = ball_center - paddle_box.closest_point(ball_center);
+                if offset.length_squared() > BALL_RADIUS * BALL_RADIUS {
+                    continue;
                 }
 
-                // Left and right collisions with paddle
-                // Check y axis
-                if transform.translation.y - BALL_RADIUS <= paddle_transform.translation.y + PADDLE_LENGTH / 2. && 
-                transform.translation.y + BALL_RADIUS >= paddle_transform.translation.y - PADDLE_LENGTH / 2. {
+                // Penetration axis: whichever side of the paddle the ball center is
+                // nearest to decides if this is a side hit or a top/bottom graze
+                let local = ball_center - paddle_center;
+                let x_penetration = PADDLE_WIDTH / 2. - local.x.abs();
+                let y_penetration = PADDLE_LENGTH / 2. - local.y.abs();
 
-                    // Check x axis
+                collision = Some(if x_penetration < y_penetration {
+                    hit_paddle_y = paddle_center.y;
                     match paddle_side {
-                        Side::Right => {
-                            if transform.translation.x + BALL_RADIUS >= paddle_transform.translation.x - PADDLE_WIDTH / 2. {
-                                collision = Some(Collision::Right);
-                            }
-                        }
-                        Side::Left => {
-                            if transform.translation.x - BALL_RADIUS <= paddle_transform.translation.x + PADDLE_WIDTH / 2. {
-                                collision = Some(Collision::Left);
-                            }
-                        }
+                        Side::Right => Collision::Right,
+                        Side::Left => Collision::Left,
                     }
-                }
+                } else if local.y > 0. {
+                    Collision::Top
+                } else {
+                    Collision::Bottom
+                });
 
-                // Top and bottom collisions with paddle
-                // Check x axis
-                if transform.translation.x + BALL_RADIUS >= paddle_transform.translation.x - PADDLE_WIDTH / 2. &&
-                transform.translation.x - BALL_RADIUS <= paddle_transform.translation.x + PADDLE_WIDTH / 2. {
-
-                    // Check y axis
-                    collision = match transform.translation.y {
-                        y if y - BALL_RADIUS <= paddle_transform.translation.y + PADDLE_LENGTH / 2. &&
-                        y - BALL_RADIUS >= paddle_transform.translation.y + PADDLE_LENGTH / 2. - (BALL_SPEED + PADDLE_SPEED) / 2. => Some(Collision::Top),
-
-                        y if y + BALL_RADIUS >= paddle_transform.translation.y - PADDLE_LENGTH / 2. &&
-                        y - BALL_RADIUS <= paddle_transform.translation.y - PADDLE_LENGTH / 2. + (BALL_SPEED + PADDLE_SPEED) / 2. => Some(Collision::Bottom),
-
-                        _ => collision
-                    };
-                }
+                break;
             }
         }
 
@@ -338,10 +543,28 @@ fn collision(
             direction.0 = match collision {
                 Collision::Top => 2. * PI - direction.0,
                 Collision::Bottom => 2. * PI - direction.0,
-                Collision::Right => PI - direction.0,
-                Collision::Left => PI - direction.0,
+                Collision::Right => {
+                    // Where the ball struck the paddle, from -1. (bottom edge) to 1. (top edge)
+                    let rel = ((transform.translation.y - hit_paddle_y) / (PADDLE_LENGTH / 2.)).clamp(-1., 1.);
+                    PI - rel * MAX_BOUNCE_ANGLE
+                }
+                Collision::Left => {
+                    let rel = ((transform.translation.y - hit_paddle_y) / (PADDLE_LENGTH / 2.)).clamp(-1., 1.);
+                    rel * MAX_BOUNCE_ANGLE
+                }
             };
 
+            // Paddle hits speed the ball up, making long rallies more intense
+            match collision {
+                Collision::Left | Collision::Right => {
+                    speed.0 = (speed.0 * BALL_SPEEDUP_MULTIPLIER).min(BALL_MAX_SPEED);
+                    paddle_hit_events.send(PaddleHitEvent);
+                }
+                Collision::Top | Collision::Bottom => {
+                    wall_hit_events.send(WallHitEvent);
+                }
+            }
+
             // Change starting point
             starting_point.x = transform.translation.x;
             starting_point.y = transform.translation.y;
@@ -357,8 +580,13 @@ fn score(
     mut player_query: Query<(&mut Score, &Side)>,
     mut score_counter_query: Query<(&mut Text, &Side)>,
     mut events: EventWriter<BallResetEvent>,
+    mut state: ResMut<State<GameState>>,
 ) {
 
+    if *state.current() != GameState::Playing {
+        return;
+    }
+
     for ball_transform in &ball_query {
         // Check if somebody received a point
         let point_side = match ball_transform.translation.x {
@@ -382,6 +610,12 @@ fn score(
                     }
 
                     events.send(BallResetEvent);
+
+                    // A winning point is left for check_for_win (running right after this
+                    // system) to move the state on to GameOver
+                    if score.0 < WINNING_SCORE {
+                        state.set(GameState::Serving).unwrap();
+                    }
                 }
             }
         }
@@ -389,7 +623,7 @@ fn score(
 }
 
 fn reset_ball(
-    mut ball_query: Query<(&mut Transform, &mut BallStartingPoint, &mut DistanceFromStartingPoint, &mut BallDirection)>,
+    mut ball_query: Query<(&mut Transform, &mut BallStartingPoint, &mut DistanceFromStartingPoint, &mut BallDirection, &mut BallSpeed)>,
     mut event_reader: EventReader<BallResetEvent>,
 ) {
 
@@ -397,7 +631,8 @@ fn reset_ball(
         for (mut transform,
             mut starting_point,
             mut distance,
-            mut direction
+            mut direction,
+            mut speed,
             ) in &mut ball_query {
 
             transform.translation.x = 0.;
@@ -406,6 +641,7 @@ fn reset_ball(
             starting_point.y = 0.;
             distance.0 = 0.;
             direction.0 = ball_first_direction();
+            speed.0 = BALL_SPEED;
         }
     }
 }
@@ -436,10 +672,137 @@ fn ball_first_direction() -> f32 {
     }
 }
 
-fn check_for_win(score_query: Query<&mut Score>, mut app_exit_events: ResMut<Events<bevy::app::AppExit>>) {
-    for score in &score_query {
-        if score.0 == 10 {
-            app_exit_events.send(bevy::app::AppExit);
+fn check_for_win(
+    score_query: Query<(&Score, &Side)>,
+    mut game_over_query: Query<&mut Text, With<GameOverText>>,
+    mut state: ResMut<State<GameState>>,
+    mut win_events: EventWriter<WinEvent>,
+) {
+    // Runs only while Playing, so once GameOver has been entered later frames don't
+    // set it again; a point below WINNING_SCORE finds no winner here
+    if *state.current() != GameState::Playing {
+        return;
+    }
+
+    for (score, side) in &score_query {
+        if score.0 >= WINNING_SCORE {
+            let winner = match side {
+                Side::Left => "Left",
+                Side::Right => "Right",
+            };
+
+            for mut text in &mut game_over_query {
+                text.sections[0].value = format!("{winner} player wins!\nPress Enter to play again");
+            }
+
+            win_events.send(WinEvent);
+            state.set(GameState::GameOver).unwrap();
         }
     }
+}
+
+// Resets the countdown whenever the ball starts a new serve
+fn enter_serving(mut countdown: ResMut<ServeCountdown>) {
+    countdown.0 = SERVE_COUNTDOWN_SECONDS;
+}
+
+fn serve_countdown(
+    time: Res<Time>,
+    mut countdown: ResMut<ServeCountdown>,
+    mut countdown_query: Query<&mut Text, With<CountdownText>>,
+    mut state: ResMut<State<GameState>>,
+) {
+    countdown.0 -= time.delta_seconds();
+
+    for mut text in &mut countdown_query {
+        text.sections[0].value = if countdown.0 > 0. {
+            (countdown.0.ceil() as u32).to_string()
+        } else {
+            String::new()
+        };
+    }
+
+    if countdown.0 <= 0. {
+        state.set(GameState::Playing).unwrap();
+    }
+}
+
+fn toggle_pause(keyboard_input: Res<Input<KeyCode>>, mut state: ResMut<State<GameState>>) {
+    if !keyboard_input.just_pressed(KeyCode::Escape) {
+        return;
+    }
+
+    match state.current().clone() {
+        GameState::Playing => state.set(GameState::Paused).unwrap(),
+        GameState::Paused => state.set(GameState::Playing).unwrap(),
+        _ => {}
+    }
+}
+
+fn restart_game(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut score_query: Query<&mut Score>,
+    mut score_counter_query: Query<&mut Text, With<Side>>,
+    mut game_over_query: Query<&mut Text, (With<GameOverText>, Without<Side>)>,
+    mut events: EventWriter<BallResetEvent>,
+    mut state: ResMut<State<GameState>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::Return) {
+        return;
+    }
+
+    for mut score in &mut score_query {
+        score.0 = 0;
+    }
+
+    for mut text in &mut score_counter_query {
+        text.sections[0].value = "0".to_string();
+    }
+
+    for mut text in &mut game_over_query {
+        text.sections[0].value = String::new();
+    }
+
+    events.send(BallResetEvent);
+    state.set(GameState::Serving).unwrap();
+}
+
+fn play_paddle_hit_sound(
+    mut events: EventReader<PaddleHitEvent>,
+    audio: Res<Audio>,
+    sounds: Res<SoundAssets>,
+) {
+    for _ in events.iter() {
+        audio.play(sounds.paddle_hit.clone());
+    }
+}
+
+fn play_wall_hit_sound(
+    mut events: EventReader<WallHitEvent>,
+    audio: Res<Audio>,
+    sounds: Res<SoundAssets>,
+) {
+    for _ in events.iter() {
+        audio.play(sounds.wall_hit.clone());
+    }
+}
+
+fn play_score_sound(
+    mut events: EventReader<BallResetEvent>,
+    audio: Res<Audio>,
+    sounds: Res<SoundAssets>,
+) {
+    for _ in events.iter() {
+        audio.play(sounds.score.clone());
+    }
+}
+
+fn play_win_sound(
+    mut events: EventReader<WinEvent>,
+    audio: Res<Audio>,
+    sounds: Res<SoundAssets>,
+) {
+    for _ in events.iter() {
+        audio.play(sounds.win.clone());
+    }
 }
\ No newline at end of file